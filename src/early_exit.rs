@@ -0,0 +1,70 @@
+//! Host-initiated early exit from a running wasm call.
+//!
+//! `register_panic` in `main` shows the alternative to this: an import
+//! stashes a [`crate::PanicInfo`] into a shared `Mutex`, and the caller
+//! has to check it after `instance.call` returns a generic `Err`,
+//! remembering to reset the `Mutex` before every call so a stale value
+//! doesn't leak across iterations.
+//!
+//! Here a host closure calls [`Ctx::terminate`] (via the [`Terminate`]
+//! extension trait) with any `Box<dyn Error + Send>`, which unwinds the
+//! wasm stack immediately, and [`call_and_downcast`] hands the original
+//! boxed error back to the caller already downcast to whatever concrete
+//! type the import used, so there's no shared state left for the caller
+//! to reset between calls.
+
+use std::cell::RefCell;
+use std::error::Error;
+use wasmer_runtime::{error, Ctx, Instance, Value};
+
+thread_local! {
+    // `instance.call` runs on the calling thread and catches the unwind
+    // from `terminate` internally, so the only way to get the original
+    // boxed error back out is to stash it here just before unwinding and
+    // take it back out right after the call returns an `Err`.
+    static TERMINATED_WITH: RefCell<Option<Box<dyn Error + Send>>> = RefCell::new(None);
+}
+
+/// Extension trait that lets a host import abort the running wasm call
+/// with a typed error instead of only being able to panic or return.
+pub trait Terminate {
+    /// Immediately unwinds the wasm stack, surfacing `err` to the host
+    /// via [`call_and_downcast`] once the call returns.
+    fn terminate(&mut self, err: Box<dyn Error + Send>) -> !;
+}
+
+impl Terminate for Ctx {
+    fn terminate(&mut self, err: Box<dyn Error + Send>) -> ! {
+        TERMINATED_WITH.with(|slot| *slot.borrow_mut() = Some(err));
+        panic!("wasm call terminated by host import");
+    }
+}
+
+/// Calls `name` on `instance`. If the call failed because a host import
+/// terminated it via [`Ctx::terminate`], the original error is downcast
+/// to `E` and returned as `Ok(Err(_))`; if `E` isn't the type that was
+/// actually passed to `terminate`, the call returns an
+/// `error::Error::RuntimeError` trap whose message includes the original
+/// error's `Display` text, so the mismatch isn't lost.
+pub fn call_and_downcast<E: Error + Send + 'static>(
+    instance: &Instance,
+    name: &str,
+    args: &[Value],
+) -> error::Result<Result<Vec<Value>, Box<E>>> {
+    let result = instance.call(name, args);
+    let terminated_with = TERMINATED_WITH.with(|slot| slot.borrow_mut().take());
+
+    match (result, terminated_with) {
+        (Ok(values), _) => Ok(Ok(values)),
+        (Err(_), Some(err)) => match err.downcast::<E>() {
+            Ok(typed) => Ok(Err(typed)),
+            Err(original) => Err(error::Error::RuntimeError(error::RuntimeError::Trap {
+                msg: format!(
+                    "wasm call was terminated with an error of an unexpected type: {original}"
+                )
+                .into(),
+            })),
+        },
+        (Err(runtime_err), None) => Err(runtime_err),
+    }
+}
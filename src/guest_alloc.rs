@@ -0,0 +1,142 @@
+//! A host-allocator protocol for returning freshly-sized data to wasm.
+//!
+//! `hello_string_from_rust` only reads a string from a pointer/length it
+//! is handed; nothing there lets a host import hand back data whose size
+//! the host only knows at call time. [`GuestAllocator`] calls back into the guest's own exported
+//! `__alloc`/`__dealloc` functions so the returned bytes live in memory
+//! the guest allocated (and is responsible for freeing), rather than the
+//! host guessing at an address or a fixed-size buffer.
+
+use crate::host_state;
+use std::fmt;
+use wasmer_runtime::{Array, Ctx, Instance, WasmPtr};
+
+/// Errors that can occur while round-tripping bytes through the guest's
+/// allocator.
+#[derive(Debug)]
+pub enum GuestAllocError {
+    /// The guest module doesn't export `__alloc`/`__dealloc` with the
+    /// expected signature, or calling `__alloc` failed.
+    MissingExport(&'static str),
+    /// The guest allocator returned a pointer that doesn't actually fit
+    /// the requested length inside the instance's memory.
+    OutOfBounds,
+}
+
+impl fmt::Display for GuestAllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestAllocError::MissingExport(name) => {
+                write!(f, "guest module doesn't export `{}`", name)
+            }
+            GuestAllocError::OutOfBounds => {
+                write!(f, "guest allocator returned a pointer out of bounds")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GuestAllocError {}
+
+/// Wraps a [`Ctx`] plus a way to reach back into the owning [`Instance`]
+/// so a host import can ask the *guest* to allocate memory for data the
+/// host wants to write back, instead of requiring the guest to
+/// pre-allocate a buffer before calling out.
+pub struct GuestAllocator<'a> {
+    ctx: &'a mut Ctx,
+    instance: *const Instance,
+}
+
+impl<'a> GuestAllocator<'a> {
+    /// Builds a [`GuestAllocator`] from the [`Ctx`] passed into a host
+    /// import, using the instance pointer stashed there by
+    /// [`host_state::install`].
+    pub fn from_ctx(ctx: &'a mut Ctx) -> Option<Self> {
+        let instance = host_state::from_ctx(ctx)?.instance() as *const Instance;
+        Some(Self { ctx, instance })
+    }
+
+    fn instance(&self) -> &Instance {
+        // SAFETY: `instance` was set by `from_ctx` to point at the
+        // `Instance` that owns `self.ctx`, which outlives every call made
+        // through it.
+        unsafe { &*self.instance }
+    }
+
+    /// Asks the guest's `__alloc` export for `bytes.len()` bytes, copies
+    /// `bytes` into the returned region, and returns a pointer the guest
+    /// can use directly (its length is `bytes.len()`).
+    ///
+    /// The caller is responsible for eventually freeing the memory,
+    /// either through [`write_bytes_guarded`](Self::write_bytes_guarded)'s
+    /// [`GuestAllocGuard`] or by having `__dealloc` called once the guest
+    /// is done with it.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<WasmPtr<u8, Array>, GuestAllocError> {
+        let alloc = self
+            .instance()
+            .func::<u32, u32>("__alloc")
+            .map_err(|_| GuestAllocError::MissingExport("__alloc"))?;
+
+        let raw_ptr = alloc
+            .call(bytes.len() as u32)
+            .map_err(|_| GuestAllocError::MissingExport("__alloc"))?;
+
+        let memory = self.ctx.memory(0);
+        let wasm_ptr = WasmPtr::<u8, Array>::new(raw_ptr);
+        let cells = wasm_ptr
+            .deref(memory, 0, bytes.len() as u32)
+            .ok_or(GuestAllocError::OutOfBounds)?;
+
+        for (cell, byte) in cells.iter().zip(bytes) {
+            cell.set(*byte);
+        }
+
+        Ok(wasm_ptr)
+    }
+
+    /// Like [`write_bytes`](Self::write_bytes), but wraps the result in a
+    /// [`GuestAllocGuard`] that calls `__dealloc` on `Drop` so the guest
+    /// memory doesn't leak if the host forgets to free it, e.g. across
+    /// repeated calls in a loop.
+    pub fn write_bytes_guarded(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<GuestAllocGuard, GuestAllocError> {
+        let ptr = self.write_bytes(bytes)?;
+        Ok(GuestAllocGuard {
+            instance: self.instance,
+            ptr,
+            len: bytes.len() as u32,
+        })
+    }
+}
+
+/// Frees the guest allocation it was created from when dropped, by
+/// calling back into the guest's `__dealloc` export.
+pub struct GuestAllocGuard {
+    instance: *const Instance,
+    ptr: WasmPtr<u8, Array>,
+    len: u32,
+}
+
+impl GuestAllocGuard {
+    pub fn ptr(&self) -> WasmPtr<u8, Array> {
+        self.ptr
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+}
+
+impl Drop for GuestAllocGuard {
+    fn drop(&mut self) {
+        // SAFETY: `instance` was captured from a `GuestAllocator` built
+        // via `host_state::from_ctx`, which only ever points at a live
+        // `Instance` that outlives this guard.
+        let instance = unsafe { &*self.instance };
+        if let Ok(dealloc) = instance.func::<(u32, u32), ()>("__dealloc") {
+            let _ = dealloc.call(self.ptr.offset(), self.len);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Opaque, capability-style handles for host resources.
+//!
+//! Imports like `increment_shared` used to reach their state purely
+//! through a closure-captured `Arc<Mutex<_>>`, which works for a single
+//! fixed resource but gives the guest no way to be handed *new* host
+//! resources without also handing it a raw address it could forge or
+//! reuse after it's freed. A [`HandleTable`] hands out randomized `u64`
+//! handles for arbitrary `Any + Send` values instead; the guest only
+//! ever sees the handle, never the value or its address, and resolving
+//! a stale or forged handle is just an error instead of undefined
+//! behaviour.
+
+use rand::Rng;
+use slab::Slab;
+use std::any::Any;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Returned when a handle the guest passed in doesn't resolve to a live
+/// value of the expected type.
+#[derive(Debug)]
+pub enum HandleError {
+    /// No value is registered under this handle (never created, or
+    /// already dropped).
+    Unknown(u64),
+    /// The handle is live, but not a handle to a `T`.
+    WrongType(u64),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::Unknown(handle) => write!(f, "unknown or dangling handle {:#x}", handle),
+            HandleError::WrongType(handle) => {
+                write!(f, "handle {:#x} is not a handle to the expected type", handle)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+/// A host-side table mapping randomized `u64` handles to arbitrary
+/// `Any + Send` values.
+///
+/// The low bits of a handle are a [`Slab`] key and the high bits are a
+/// per-slot random salt, so a handle can't be forged from a guessed slab
+/// index alone the way a bare array index could be, nor reused once its
+/// slot has been recycled for something else.
+pub struct HandleTable {
+    inner: Mutex<Slab<(u32, Box<dyn Any + Send>)>>,
+}
+
+fn pack(key: usize, salt: u32) -> u64 {
+    // `unpack` only ever reads the low 32 bits back out as the slab key,
+    // so a key that somehow exceeded u32::MAX would be silently truncated
+    // by the mask below and the handle would point at a different slot.
+    debug_assert!(key <= u32::MAX as usize, "slab key overflowed u32");
+    ((salt as u64) << 32) | (key as u64 & 0xFFFF_FFFF)
+}
+
+fn unpack(handle: u64) -> (usize, u32) {
+    ((handle & 0xFFFF_FFFF) as usize, (handle >> 32) as u32)
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Slab::new()),
+        }
+    }
+
+    /// Registers `value` and returns the opaque handle the guest should
+    /// be given to refer to it.
+    pub fn create<T: Any + Send>(&self, value: T) -> u64 {
+        let salt: u32 = rand::thread_rng().gen();
+        let mut slab = self.inner.lock().unwrap();
+        let key = slab.insert((salt, Box::new(value)));
+        pack(key, salt)
+    }
+
+    /// Removes `handle` from the table, returning `true` if it was live.
+    /// A dropped (or already-unknown) handle simply stops resolving.
+    pub fn drop_handle(&self, handle: u64) -> bool {
+        let (key, salt) = unpack(handle);
+        let mut slab = self.inner.lock().unwrap();
+        match slab.get(key) {
+            Some((s, _)) if *s == salt => {
+                slab.remove(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Resolves `handle` to a clone of the `T` it was created with, e.g.
+    /// an `Arc<Mutex<_>>` a host import can then lock.
+    pub fn resolve<T: Any + Send + Clone>(&self, handle: u64) -> Result<T, HandleError> {
+        let (key, salt) = unpack(handle);
+        let slab = self.inner.lock().unwrap();
+        match slab.get(key) {
+            Some((s, value)) if *s == salt => value
+                .downcast_ref::<T>()
+                .cloned()
+                .ok_or(HandleError::WrongType(handle)),
+            _ => Err(HandleError::Unknown(handle)),
+        }
+    }
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,75 @@
+//! The single piece of host-side state reachable from a running `Ctx`.
+//!
+//! Several subsystems need to call back into the owning [`Instance`] (or
+//! shared host state) from inside a host import, where only `&mut Ctx` is
+//! available: [`crate::guest_alloc`] needs the instance to call its
+//! `__alloc`/`__dealloc` exports, and [`crate::handles`] needs the handle
+//! table to resolve an opaque handle into the real host value. Both ride
+//! on the same mechanism, stashing one [`HostState`] in `Ctx::data` right
+//! after `instantiate` rather than each subsystem fighting over that one
+//! pointer-sized slot.
+
+use crate::handles::{HandleError, HandleTable};
+use std::any::Any;
+use std::sync::Arc;
+use wasmer_runtime::{Ctx, Instance};
+
+pub struct HostState {
+    instance: *const Instance,
+    /// An `Arc` rather than an owned `HandleTable` so that several
+    /// instances sharing one import object (see [`crate::script_host`])
+    /// can also share one table, and a handle created against it resolves
+    /// no matter which of those instances' `Ctx` it's resolved from.
+    pub handles: Arc<HandleTable>,
+}
+
+/// Stashes `state` in `instance`'s own [`Ctx`] so that host imports
+/// running inside it can reach it back via [`from_ctx`].
+///
+/// Must be called once, right after `instantiate`, before any import that
+/// relies on [`from_ctx`] can run.
+pub fn install(instance: &mut Instance, handles: Arc<HandleTable>) {
+    let self_ptr: *const Instance = instance;
+    let state = Box::new(HostState {
+        instance: self_ptr,
+        handles,
+    });
+    instance.context_mut().data = Box::into_raw(state) as *mut std::ffi::c_void;
+}
+
+/// Reads back the [`HostState`] stashed by [`install`], if any.
+pub fn from_ctx(ctx: &Ctx) -> Option<&HostState> {
+    let state = ctx.data as *const HostState;
+    if state.is_null() {
+        None
+    } else {
+        // SAFETY: `install` only ever stores a `Box<HostState>` leaked via
+        // `Box::into_raw`, and the instance it came from outlives every
+        // call made through `ctx`.
+        Some(unsafe { &*state })
+    }
+}
+
+impl HostState {
+    /// The [`Instance`] this state was installed into.
+    pub fn instance(&self) -> &Instance {
+        // SAFETY: see `from_ctx`.
+        unsafe { &*self.instance }
+    }
+}
+
+/// Lets a host import resolve an opaque handle straight from the `Ctx` it
+/// was already given, instead of threading the [`HandleTable`] through
+/// every closure by hand.
+pub trait ResolveHandle {
+    fn resolve<T: Any + Send + Clone>(&self, handle: u64) -> Result<T, HandleError>;
+}
+
+impl ResolveHandle for Ctx {
+    fn resolve<T: Any + Send + Clone>(&self, handle: u64) -> Result<T, HandleError> {
+        match from_ctx(self) {
+            Some(state) => state.handles.resolve(handle),
+            None => Err(HandleError::Unknown(handle)),
+        }
+    }
+}
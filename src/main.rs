@@ -1,11 +1,36 @@
 extern crate wasmer_runtime;
 
+mod early_exit;
+mod guest_alloc;
+mod handles;
+mod host_state;
+mod metering;
+mod script_host;
+mod tunables;
+
 use std::{
     fmt,
     sync::{Arc, Mutex},
 };
 use wasmer_runtime::{error, func, imports, instantiate, Array, Ctx, WasmPtr};
 
+use early_exit::Terminate;
+use host_state::ResolveHandle;
+
+/// Returned by the `print_str` import via [`Ctx::terminate`] when the
+/// guest hands over a pointer/length that doesn't decode as utf8, instead
+/// of letting the host-side `.unwrap()` panic turn into an opaque trap.
+#[derive(Debug)]
+pub struct InvalidUtf8;
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("guest passed a string that wasn't valid utf8")
+    }
+}
+
+impl std::error::Error for InvalidUtf8 {}
+
 // Make sure that the compiled wasm-sample-app is accessible at this path.
 static WASM: &'static [u8] =
     include_bytes!("../wasm-sample-app/target/wasm32-unknown-unknown/release/wasm_sample_app.wasm");
@@ -38,7 +63,21 @@ impl fmt::Display for PanicInfo {
     }
 }
 
-fn main() -> error::Result<()> {
+/// Everything a freshly built import object needs to be usable: the
+/// object itself, plus the host-side state its closures share with
+/// whatever `main` (or a [`script_host::ScriptHost`]) does with the
+/// instance(s) it gets instantiated into.
+struct HostImports {
+    import_object: wasmer_runtime::ImportObject,
+    panic_info: Arc<Mutex<Option<PanicInfo>>>,
+    handle_table: Arc<handles::HandleTable>,
+}
+
+/// Builds a fresh `import_object` wiring up every host function the
+/// sample wasm module imports. Split out of `main` so a
+/// [`script_host::ScriptHost`] managing several instances can build one
+/// shared import object the same way `main` builds its own.
+fn build_import_object() -> HostImports {
     // create shared data that we'll use in 2 host functions
     let shared_data = Arc::new(Mutex::new(0usize));
 
@@ -56,12 +95,29 @@ fn main() -> error::Result<()> {
         println!("{}: {}", guard, string);
     };
 
-    // Copy the [`Arc`] and move it into the closure
-    let data = Arc::clone(&shared_data);
-    let increment_shared = move || {
-        // get the shared data and increment it
-        let mut guard = data.lock().unwrap();
-        *guard += 1;
+    // Register the shared data behind an opaque handle instead of handing
+    // the guest a raw pointer it could otherwise forge; `get_shared_handle`
+    // is how the guest learns the handle, and `increment_shared` is the
+    // only thing that can resolve it back to the real `Arc<Mutex<usize>>`.
+    let handle_table = Arc::new(handles::HandleTable::new());
+    let shared_handle = handle_table.create(Arc::clone(&shared_data));
+
+    let get_shared_handle = move || -> i64 { shared_handle as i64 };
+
+    let increment_shared = |ctx: &mut Ctx, handle: i64| {
+        match ctx.resolve::<Arc<Mutex<usize>>>(handle as u64) {
+            Ok(data) => {
+                let mut guard = data.lock().unwrap();
+                *guard += 1;
+            }
+            Err(err) => ctx.terminate(Box::new(err)),
+        }
+    };
+
+    let handle_drop = |ctx: &mut Ctx, handle: i64| {
+        if let Some(state) = host_state::from_ctx(ctx) {
+            state.handles.drop_handle(handle as u64);
+        }
     };
 
     let panic_info = Arc::new(Mutex::new(None));
@@ -90,6 +146,40 @@ fn main() -> error::Result<()> {
         (*pi.lock().unwrap()) = Some(panic_info);
     };
 
+    // Uppercases the guest's string and hands the result back through
+    // memory the guest allocated itself, instead of writing into a
+    // fixed-size buffer the guest would have to pre-size.
+    let echo_upper = |ctx: &mut Ctx,
+                      ptr: WasmPtr<u8, Array>,
+                      len: u32,
+                      out_ptr: WasmPtr<u32>,
+                      out_len: WasmPtr<u32>| {
+        let upper = {
+            let memory = ctx.memory(0);
+            let string = ptr.get_utf8_string(memory, len).unwrap();
+            string.to_uppercase()
+        };
+
+        let mut allocator = match guest_alloc::GuestAllocator::from_ctx(ctx) {
+            Some(allocator) => allocator,
+            None => return,
+        };
+
+        // Unguarded: this memory is handed back to the guest via the
+        // out-params below, so it's still in use when this import
+        // returns. The guest frees it itself (via `__dealloc`) once it's
+        // done reading, as `echo_upper_demo` does.
+        if let Ok(wasm_ptr) = allocator.write_bytes(upper.as_bytes()) {
+            let memory = ctx.memory(0);
+            if let Some(cell) = out_ptr.deref(memory) {
+                cell.set(wasm_ptr.offset());
+            }
+            if let Some(cell) = out_len.deref(memory) {
+                cell.set(upper.len() as u32);
+            }
+        }
+    };
+
     // Let's define the import object used to import our function
     // into our webassembly sample application.
     //
@@ -111,14 +201,44 @@ fn main() -> error::Result<()> {
             "print_str2" => func!(print_str2),
             "increment_shared" => func!(increment_shared),
             "register_panic" => func!(register_panic),
+            "echo_upper" => func!(echo_upper),
+            "get_shared_handle" => func!(get_shared_handle),
+            "handle_drop" => func!(handle_drop),
         },
     };
 
+    HostImports {
+        import_object,
+        panic_info,
+        handle_table,
+    }
+}
+
+fn main() -> error::Result<()> {
+    let HostImports {
+        import_object,
+        panic_info,
+        handle_table,
+    } = build_import_object();
+
     // Compile our webassembly into an `Instance`.
-    let instance = instantiate(WASM, &import_object)?;
+    let mut instance = instantiate(WASM, &import_object)?;
 
-    // Call our exported function!
-    instance.call("hello_wasm", &[])?;
+    // `echo_upper` needs to call back into this instance's own `__alloc`
+    // export, and `increment_shared`/`handle_drop` need the handle table,
+    // so give their `Ctx` a way to reach both.
+    host_state::install(&mut instance, Arc::clone(&handle_table));
+
+    // Call our exported function! `print_str` can terminate this call
+    // early with a typed `InvalidUtf8` if the guest passes it garbage, so
+    // we use `call_and_downcast` instead of a plain `instance.call` to be
+    // able to tell that apart from any other failure.
+    match early_exit::call_and_downcast::<InvalidUtf8>(&instance, "hello_wasm", &[])? {
+        Ok(_) => {}
+        Err(invalid_utf8) => panic!("hello_wasm rejected by the host: {}", invalid_utf8),
+    }
+
+    instance.call("echo_upper_demo", &[])?;
 
     for i in 0..4 {
         // Reset panic information before every call to ensure a previous
@@ -137,6 +257,75 @@ fn main() -> error::Result<()> {
         }
     }
 
+    // A metered instance bounds how much work a single call (or a loop of
+    // them, like the one above) can do before it's forcibly trapped, which
+    // protects the host against a module that's runaway or malicious
+    // rather than just panicking.
+    let metered =
+        metering::instantiate_metered(WASM, &import_object, Arc::clone(&handle_table), 10_000)?;
+    match metered.call_metered("fails", &[], 10_000) {
+        Ok(_) => panic!("calling 'fails' should have returned an error"),
+        Err(metering::MeteringError::FuelExhausted) => {
+            println!("metered call to 'fails' ran out of fuel");
+        }
+        Err(metering::MeteringError::Call(_)) => {
+            println!(
+                "metered call to 'fails' failed with {} points remaining",
+                metered.get_remaining_points()
+            );
+        }
+    }
+
+    // A ScriptHost can manage several modules (here, just two copies of
+    // the same one) against a single shared import object, caching each
+    // module's resolved, type-checked function handles instead of
+    // re-resolving "fails" by name on every call.
+    let script_imports = build_import_object();
+    let mut script_host =
+        script_host::ScriptHost::new(script_imports.import_object, script_imports.handle_table);
+    let first = script_host.add_module(WASM);
+    let second = script_host.add_module(WASM);
+    let script_host = script_host.finalize()?;
+
+    for (label, module) in [("first", first), ("second", second)] {
+        (*script_imports.panic_info.lock().unwrap()) = None;
+        match script_host.call::<(), ()>(module, "fails", ()) {
+            Ok(_) => panic!("calling 'fails' through the ScriptHost should have failed"),
+            Err(_) => match *script_imports.panic_info.lock().unwrap() {
+                Some(ref pi) => {
+                    println!("ScriptHost's {} module captured panic '{}'", label, pi)
+                }
+                None => println!(
+                    "ScriptHost's {} module failed to capture panic information",
+                    label
+                ),
+            },
+        }
+    }
+
+    // A budgeted instance protects the host from a guest that balloons
+    // memory across repeated calls (e.g. the panic-and-retry loop above)
+    // by capping its page count and refusing further calls past it.
+    let budgeted_imports = build_import_object();
+    let budgeted = tunables::instantiate_with_config(
+        WASM,
+        &budgeted_imports.import_object,
+        Arc::clone(&budgeted_imports.handle_table),
+        tunables::Tunables::new()
+            .with_max_pages(16)
+            .with_growth_callback(|old_pages, new_pages| {
+                println!("guest memory grew from {} to {} pages", old_pages, new_pages);
+            }),
+    )?;
+    (*budgeted_imports.panic_info.lock().unwrap()) = None;
+    match budgeted.call("fails", &[]) {
+        Ok(_) => panic!("calling 'fails' through the budgeted instance should have failed"),
+        Err(_) => match *budgeted_imports.panic_info.lock().unwrap() {
+            Some(ref pi) => println!("budgeted instance captured panic '{}'", pi),
+            None => println!("budgeted instance failed to capture panic information"),
+        },
+    }
+
     Ok(())
 }
 
@@ -155,7 +344,13 @@ fn print_str(ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, len: u32) {
     let memory = ctx.memory(0);
 
     // Use helper method on `WasmPtr` to read a utf8 string
-    let string = ptr.get_utf8_string(memory, len).unwrap();
+    let string = match ptr.get_utf8_string(memory, len) {
+        Some(string) => string,
+        // Rather than letting this `.unwrap()` panic and surface as an
+        // opaque trap, reject the bad input with a typed error the host
+        // can recover with `early_exit::call_and_downcast`.
+        None => ctx.terminate(Box::new(InvalidUtf8)),
+    };
 
     // Print it!
     println!("{}", string);
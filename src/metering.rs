@@ -0,0 +1,134 @@
+//! Opt-in execution metering ("gas") for wasm instances.
+//!
+//! The fuel budget below isn't just a host-side counter the embedder
+//! pinky-promises to check: [`instantiate_metered`] compiles the module
+//! with `wasmer_middleware_common`'s `Metering` codegen middleware, which
+//! is what actually injects the per-basic-block decrement and the trap on
+//! underflow. [`MeteredInstance`] is a thin wrapper around the points the
+//! *compiled code* is tracking (`metering::get_points_used`/
+//! `set_points_used`), not an independent budget, so it stays in sync with
+//! whatever the guest is actually doing instead of trusting it.
+//!
+//! `Metering` charges a flat cost per instruction and takes only a single
+//! `limit`; it has no hook for pricing opcodes differently by category
+//! (e.g. calls or memory accesses costing more than arithmetic), so
+//! there's no `CostFunction`-style knob here the way there would be for a
+//! hand-rolled counter. Weighting individual opcodes would mean writing a
+//! custom `FunctionMiddleware` instead of reusing this one.
+
+use std::sync::{Arc, Mutex};
+use wasmer_middleware_common::metering;
+use wasmer_runtime::{error, ImportObject, Instance, Value};
+use wasmer_runtime_core::backend::Compiler;
+use wasmer_runtime_core::codegen::{MiddlewareChain, StreamingCompiler};
+use wasmer_singlepass_backend::ModuleCodeGenerator as SinglePassMCG;
+
+use crate::handles::HandleTable;
+
+/// Returned by [`MeteredInstance::call_metered`] when the guest ran out
+/// of fuel before the call returned.
+#[derive(Debug)]
+pub enum MeteringError {
+    /// The compiled-in metering trapped the call because its points ran
+    /// out.
+    FuelExhausted,
+    /// The call failed for a reason unrelated to metering.
+    Call(error::Error),
+}
+
+impl std::fmt::Display for MeteringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeteringError::FuelExhausted => f.write_str("instance ran out of metering fuel"),
+            MeteringError::Call(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MeteringError {}
+
+fn metering_compiler(limit: u64) -> impl Compiler {
+    StreamingCompiler::<SinglePassMCG, _, _, _, _>::new(move || {
+        let mut chain = MiddlewareChain::new();
+        chain.push(metering::Metering::new(limit));
+        chain
+    })
+}
+
+/// A wasm [`Instance`] compiled with metering, paired with the limit it
+/// was compiled with.
+///
+/// `limit` is tracked separately from `metering::get_points_used` because
+/// the middleware only counts points *consumed*; subtracting the two is
+/// what gives the host "points remaining".
+pub struct MeteredInstance {
+    instance: Box<Instance>,
+    limit: Mutex<u64>,
+}
+
+impl MeteredInstance {
+    fn new(instance: Box<Instance>, limit: u64) -> Self {
+        metering::set_points_used(&instance, 0);
+        Self {
+            instance,
+            limit: Mutex::new(limit),
+        }
+    }
+
+    /// Returns the number of points left before the instance traps.
+    pub fn get_remaining_points(&self) -> u64 {
+        let limit = *self.limit.lock().unwrap();
+        limit.saturating_sub(metering::get_points_used(&self.instance))
+    }
+
+    /// Resets the instance's budget to `points`, e.g. between iterations
+    /// of a call loop that reuses one instance (like the `fails` loop in
+    /// `main`). The trap point is compiled in, so this works by presetting
+    /// the points already used; `points` above the `limit` the instance
+    /// was compiled with is capped at that limit.
+    pub fn set_remaining_points(&self, points: u64) {
+        let limit = *self.limit.lock().unwrap();
+        metering::set_points_used(&self.instance, limit.saturating_sub(points));
+    }
+
+    /// Calls an exported function with `points` worth of fuel, returning
+    /// [`MeteringError::FuelExhausted`] if the metering middleware
+    /// trapped the call before it could return.
+    pub fn call_metered(
+        &self,
+        name: &str,
+        args: &[Value],
+        points: u64,
+    ) -> Result<Vec<Value>, MeteringError> {
+        self.set_remaining_points(points);
+
+        self.instance.call(name, args).map_err(|err| {
+            if self.get_remaining_points() == 0 {
+                MeteringError::FuelExhausted
+            } else {
+                MeteringError::Call(err)
+            }
+        })
+    }
+}
+
+/// Compiles `wasm` with the metering middleware active, instantiates it
+/// against `import_object`, and wraps the result in a [`MeteredInstance`]
+/// seeded with `limit` points.
+pub fn instantiate_metered(
+    wasm: &[u8],
+    import_object: &ImportObject,
+    handles: Arc<HandleTable>,
+    limit: u64,
+) -> error::Result<MeteredInstance> {
+    let module = wasmer_runtime_core::compile_with(wasm, &metering_compiler(limit))?;
+    // Box (pinning the `Instance`'s address) before installing host state
+    // that points back at it, same as `script_host::finalize` and
+    // `tunables::instantiate_with_config`.
+    let mut instance = Box::new(module.instantiate(import_object)?);
+    // Without this, imports like `increment_shared`/`echo_upper` fail
+    // `ctx.resolve`/`GuestAllocator::from_ctx` when called through a
+    // `MeteredInstance`, regardless of how much fuel is left.
+    crate::host_state::install(&mut instance, handles);
+    Ok(MeteredInstance::new(instance, limit))
+}
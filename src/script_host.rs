@@ -0,0 +1,114 @@
+//! A reusable host for several wasm modules sharing one import object.
+//!
+//! `main` hardcodes a single `include_bytes!` module and re-resolves
+//! `"hello_wasm"`/`"fails"` by name (and re-validates their signature)
+//! every time it calls them. [`ScriptHost`] instead loads several modules
+//! against a shared import object (so they can all see the same host
+//! state, e.g. `shared_data`) and caches resolved, signature-checked
+//! function handles so repeated calls skip the export lookup after the
+//! first one.
+
+use crate::handles::HandleTable;
+use crate::host_state;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wasmer_runtime::{error, Func, ImportObject, Instance, WasmTypeList};
+
+/// Identifies one of the modules loaded into a [`ScriptHost`], in the
+/// order they were passed to [`ScriptHost::add_module`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(usize);
+
+/// Loads, instantiates, and calls into several wasm modules that all
+/// share one import object (and therefore one view of any host state,
+/// like `shared_data`, wired into that import object).
+pub struct ScriptHost {
+    import_object: ImportObject,
+    // Shared by every instance this host manages (via `host_state`), so a
+    // handle baked into the import object's closures (like the demo
+    // `get_shared_handle` in `main`) resolves no matter which instance's
+    // `Ctx` it's resolved from.
+    handles: Arc<HandleTable>,
+    pending: Vec<Vec<u8>>,
+    // `Box<Instance>` so the `Instance`'s heap allocation doesn't move
+    // even if this `Vec` reallocates; `call` caches `Func`s that
+    // (unsafely) borrow from it for `'static`, see `call` below.
+    modules: Vec<Box<Instance>>,
+    cache: Mutex<HashMap<(usize, String), Box<dyn Any + Send>>>,
+}
+
+impl ScriptHost {
+    pub fn new(import_object: ImportObject, handles: Arc<HandleTable>) -> Self {
+        Self {
+            import_object,
+            handles,
+            pending: Vec::new(),
+            modules: Vec::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queues `wasm` to be compiled and instantiated once [`finalize`]
+    /// runs, and returns the [`ModuleId`] it will be instantiated under.
+    ///
+    /// [`finalize`]: Self::finalize
+    pub fn add_module(&mut self, wasm: &[u8]) -> ModuleId {
+        let id = ModuleId(self.pending.len());
+        self.pending.push(wasm.to_vec());
+        id
+    }
+
+    /// Compiles and instantiates every module queued by [`add_module`]
+    /// against the shared import object.
+    ///
+    /// [`add_module`]: Self::add_module
+    pub fn finalize(mut self) -> error::Result<Self> {
+        for wasm in self.pending.drain(..) {
+            let instance = wasmer_runtime::instantiate(&wasm, &self.import_object)?;
+            // Box (pinning the `Instance`'s address) *before* installing
+            // host state that points back at it; installing on the
+            // stack-local `instance` and boxing afterward would leave
+            // `HostState` pointing at a stack slot that's reused on the
+            // next loop iteration.
+            let mut instance = Box::new(instance);
+            host_state::install(&mut instance, Arc::clone(&self.handles));
+            self.modules.push(instance);
+        }
+        Ok(self)
+    }
+
+    fn instance(&self, module: ModuleId) -> &Instance {
+        &self.modules[module.0]
+    }
+
+    /// Calls `name` in `module` with `args`, resolving and type-checking
+    /// the export only the first time it's asked for; later calls to the
+    /// same `(module, name)` reuse the cached handle.
+    pub fn call<Args, Rets>(&self, module: ModuleId, name: &str, args: Args) -> error::Result<Rets>
+    where
+        Args: WasmTypeList + Any,
+        Rets: WasmTypeList + Any,
+    {
+        let key = (module.0, name.to_owned());
+        let mut cache = self.cache.lock().unwrap();
+
+        if !cache.contains_key(&key) {
+            let func = self.instance(module).func::<Args, Rets>(name)?;
+            // SAFETY: `func` borrows the `Instance` at `self.modules[module.0]`,
+            // whose heap allocation (and thus this pointer) stays valid for
+            // as long as `self` does, since `modules` is never shrunk or
+            // replaced after `finalize`.
+            let func: Func<'static, Args, Rets> = unsafe { std::mem::transmute(func) };
+            cache.insert(key.clone(), Box::new(func));
+        }
+
+        let func = cache
+            .get(&key)
+            .unwrap()
+            .downcast_ref::<Func<'static, Args, Rets>>()
+            .expect("cached function handle was stored under the wrong Args/Rets");
+
+        func.call(args)
+    }
+}
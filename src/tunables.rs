@@ -0,0 +1,155 @@
+//! Configurable memory limits, checked around calls.
+//!
+//! `instantiate` gives a guest unbounded linear memory, so a module that
+//! panics-then-retries in a loop (like `fails`) could still balloon
+//! memory on each attempt. [`Tunables`] lets the embedder cap the
+//! guest's maximum page count and observe growth via a callback.
+//!
+//! This `wasmer_runtime` version doesn't expose a hook into the
+//! `memory.grow` instruction itself (that would mean a custom codegen
+//! middleware, the way [`crate::metering`] instruments basic blocks), so
+//! [`BudgetedInstance`] can only check the guest's memory size around
+//! calls, not veto a single call's growth mid-flight: a call that grows
+//! memory past `max_pages` still runs to completion (though it returns
+//! the budget error in place of its own result), and
+//! [`BudgetedInstance::call`] refuses to start any *further* call once the
+//! budget has been exceeded. That's enough to bound the panic-and-retry pattern above
+//! (each retry is a separate call), but not a guest that blows the
+//! budget in one shot.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use wasmer_runtime::{error, ImportObject, Instance, Value};
+
+/// Called with `(old_pages, new_pages)` whenever a call is observed to
+/// have grown the guest's memory. Purely informational: by the time this
+/// runs the growth has already happened, so it can't reject it (see the
+/// module docs for why).
+pub type GrowthCallback = Arc<dyn Fn(u32, u32) + Send + Sync>;
+
+/// Configuration for [`instantiate_with_config`].
+#[derive(Clone, Default)]
+pub struct Tunables {
+    max_pages: Option<u32>,
+    on_memory_grow: Option<GrowthCallback>,
+}
+
+impl Tunables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps the guest's memory at `pages` 64KiB wasm pages.
+    pub fn with_max_pages(mut self, pages: u32) -> Self {
+        self.max_pages = Some(pages);
+        self
+    }
+
+    /// Registers a callback invoked whenever the guest's memory is
+    /// observed to have grown (after the fact; see the module docs).
+    pub fn with_growth_callback(
+        mut self,
+        callback: impl Fn(u32, u32) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_memory_grow = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// Returned when a call would leave (or left) the guest's memory over its
+/// configured page budget.
+#[derive(Debug)]
+pub struct MemoryBudgetExceeded {
+    pub pages: u32,
+    pub max_pages: u32,
+}
+
+impl fmt::Display for MemoryBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "guest memory grew to {} pages, over the {} page budget",
+            self.pages, self.max_pages
+        )
+    }
+}
+
+impl std::error::Error for MemoryBudgetExceeded {}
+
+/// An [`Instance`] whose memory growth is checked against a [`Tunables`]
+/// budget around every call.
+pub struct BudgetedInstance {
+    instance: Box<Instance>,
+    tunables: Tunables,
+    last_seen_pages: Mutex<u32>,
+}
+
+impl BudgetedInstance {
+    /// Calls `name` on the wrapped instance, refusing to start if a past
+    /// call already left the guest's memory over budget, and checking
+    /// again afterward: if this call put it over, its own result is
+    /// replaced by the budget error and every later call is refused.
+    /// Doesn't (can't, see the module docs) stop this call itself from
+    /// growing memory past the budget.
+    pub fn call(&self, name: &str, args: &[Value]) -> error::Result<Vec<Value>> {
+        self.enforce_budget()?;
+        let result = self.instance.call(name, args);
+        self.enforce_budget()?;
+        result
+    }
+
+    fn current_pages(&self) -> u32 {
+        self.instance.context().memory(0).size().0
+    }
+
+    fn enforce_budget(&self) -> error::Result<()> {
+        let pages = self.current_pages();
+        let mut last_seen = self.last_seen_pages.lock().unwrap();
+
+        if pages > *last_seen {
+            if let Some(callback) = &self.tunables.on_memory_grow {
+                callback(*last_seen, pages);
+            }
+            *last_seen = pages;
+        }
+
+        if let Some(max_pages) = self.tunables.max_pages {
+            if pages > max_pages {
+                return Err(budget_error(MemoryBudgetExceeded { pages, max_pages }));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn budget_error(err: MemoryBudgetExceeded) -> error::Error {
+    error::Error::RuntimeError(error::RuntimeError::Trap {
+        msg: format!("{}", err).into(),
+    })
+}
+
+/// Instantiates `wasm` with `import_object`, wrapping the result in a
+/// [`BudgetedInstance`] that enforces `tunables` around every call.
+pub fn instantiate_with_config(
+    wasm: &[u8],
+    import_object: &ImportObject,
+    handles: Arc<crate::handles::HandleTable>,
+    tunables: Tunables,
+) -> error::Result<BudgetedInstance> {
+    let instance = wasmer_runtime::instantiate(wasm, import_object)?;
+    // Box (pinning the `Instance`'s address) before installing host state
+    // that points back at it, the same way `script_host::finalize` does -
+    // installing on the stack-local `instance` and boxing afterward would
+    // leave `HostState` pointing at a stack slot this function no longer
+    // owns once it returns.
+    let mut instance = Box::new(instance);
+    crate::host_state::install(&mut instance, handles);
+    let last_seen_pages = instance.context().memory(0).size().0;
+
+    Ok(BudgetedInstance {
+        instance,
+        tunables,
+        last_seen_pages: Mutex::new(last_seen_pages),
+    })
+}
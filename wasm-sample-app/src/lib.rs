@@ -6,7 +6,11 @@ use std::str;
 extern "C" {
     fn print_str(ptr: *const u8, len: usize);
     fn print_str2(ptr: *const u8, len: usize);
-    fn increment_shared();
+    // Resolves to the real shared counter on the host side; the guest
+    // never sees anything but this opaque handle.
+    fn get_shared_handle() -> i64;
+    fn increment_shared(handle: i64);
+    fn handle_drop(handle: i64);
     fn register_panic(
         msg_ptr: *const u8,
         msg_len: u32,
@@ -15,6 +19,30 @@ extern "C" {
         line: u32,
         column: u32,
     );
+    // Writes the uppercased copy of the string at `ptr`/`len` into memory
+    // this module itself allocated (via `__alloc`, below), and reports
+    // where it ended up through the two out-params.
+    fn echo_upper(ptr: *const u8, len: usize, out_ptr: *mut u32, out_len: *mut u32);
+}
+
+// Called by the host through `GuestAllocator` whenever it needs to hand
+// freshly-computed data back into this module's memory, e.g. from
+// `echo_upper`.
+#[no_mangle]
+pub extern "C" fn __alloc(len: usize) -> *mut u8 {
+    let mut buf: Vec<u8> = Vec::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+// Frees memory previously handed out by `__alloc`, once the host is done
+// writing into it and the guest is done reading it.
+#[no_mangle]
+pub extern "C" fn __dealloc(ptr: *mut u8, len: usize) {
+    unsafe {
+        drop(Vec::from_raw_parts(ptr, len, len));
+    }
 }
 
 // Define a string that is accessible within the wasm
@@ -30,9 +58,12 @@ pub extern "C" fn hello_wasm() {
     unsafe {
         print_str(HELLO.as_ptr(), HELLO.len());
         print_str2(HELLO.as_ptr(), HELLO.len());
-        increment_shared();
-        increment_shared();
+
+        let shared = get_shared_handle();
+        increment_shared(shared);
+        increment_shared(shared);
         print_str2(HELLO.as_ptr(), HELLO.len());
+        handle_drop(shared);
     }
 }
 
@@ -46,6 +77,22 @@ pub extern "C" fn hello_string_from_rust(ptr: i32, len: i32) {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn echo_upper_demo() {
+    let mut out_ptr: u32 = 0;
+    let mut out_len: u32 = 0;
+    unsafe {
+        echo_upper(
+            HELLO.as_ptr(),
+            HELLO.len(),
+            &mut out_ptr as *mut u32,
+            &mut out_len as *mut u32,
+        );
+        print_str(out_ptr as *const u8, out_len as usize);
+        __dealloc(out_ptr as *mut u8, out_len as usize);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn fails() {
     register_panic_hook();